@@ -1,9 +1,20 @@
-use std::{future::Future, sync::Arc, sync::Mutex};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use async_task::FallibleTask;
+use futures_lite::FutureExt;
 
 use super::{executor::global_executor, select, CondWait, Either, Executor};
 
+/// How often `TaskGroup::join` polls for the group to become empty.
+const JOIN_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+
 /// TaskGroup A group that contains spawned tasks.
 ///
 /// # Example
@@ -28,7 +39,8 @@ use super::{executor::global_executor, select, CondWait, Either, Executor};
 /// ```
 ///
 pub struct TaskGroup<'a> {
-    tasks: Mutex<Vec<TaskHandler>>,
+    tasks: Arc<Mutex<HashMap<u64, TaskHandler>>>,
+    next_id: AtomicU64,
     stop_signal: Arc<CondWait>,
     executor: Executor<'a>,
 }
@@ -39,7 +51,8 @@ impl TaskGroup<'static> {
     /// This will spawn a task onto a global executor (single-threaded by default).
     pub fn new() -> Self {
         Self {
-            tasks: Mutex::new(Vec::new()),
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+            next_id: AtomicU64::new(0),
             stop_signal: Arc::new(CondWait::new()),
             executor: global_executor(),
         }
@@ -50,12 +63,43 @@ impl<'a> TaskGroup<'a> {
     /// Creates a new TaskGroup by providing an executor
     pub fn with_executor(executor: Executor<'a>) -> Self {
         Self {
-            tasks: Mutex::new(Vec::new()),
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+            next_id: AtomicU64::new(0),
             stop_signal: Arc::new(CondWait::new()),
             executor,
         }
     }
 
+    /// Creates a child `TaskGroup` that shares this group's executor.
+    ///
+    /// Cancelling the parent cascades to the child (and transitively to
+    /// any of the child's own children), tearing down the whole subtree.
+    /// Cancelling the child directly only affects the child's own tasks
+    /// and leaves the parent and siblings untouched.
+    pub fn child(&self) -> TaskGroup<'a> {
+        let child = TaskGroup::with_executor(self.executor.clone());
+
+        // Forwards cancellation from the parent down to the child. The
+        // task's own future resolves as soon as the child's stop signal
+        // fires on its own (the child was cancelled directly, or dropped
+        // - see `Drop` below), so this is always reaped promptly instead
+        // of living in the parent's map for the parent's entire lifetime.
+        let wait_signal = child.stop_signal.clone();
+        let broadcast_signal = child.stop_signal.clone();
+        self.spawn(
+            async move { wait_signal.wait().await },
+            move |result| async move {
+                if let TaskResult::Cancelled = result {
+                    // The parent was cancelled first; cascade that down to
+                    // the child before this task is reaped.
+                    broadcast_signal.broadcast().await;
+                }
+            },
+        );
+
+        child
+    }
+
     /// Spawns a new task and calls the callback after it has completed
     /// or been canceled. The callback will have the `TaskResult` as a
     /// parameter, indicating whether the task completed or was canceled.
@@ -66,13 +110,57 @@ impl<'a> TaskGroup<'a> {
         CallbackF: FnOnce(TaskResult<T>) -> CallbackFut + Send + 'a,
         CallbackFut: Future<Output = ()> + Send + 'a,
     {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let tasks = self.tasks.clone();
+
+        // Holding the lock across `TaskHandler::new` and the insert below
+        // prevents the task from reaping itself (on the executor thread)
+        // before it's actually in the map.
+        let mut tasks_guard = self.tasks.lock().unwrap();
         let task = TaskHandler::new(
             self.executor.clone(),
             fut,
             callback,
             self.stop_signal.clone(),
+            move || {
+                tasks.lock().unwrap().remove(&id);
+            },
         );
-        self.tasks.lock().unwrap().push(task);
+        tasks_guard.insert(id, task);
+    }
+
+    /// Spawns a new task and returns a `Task<T>` handle for it, in addition
+    /// to tracking it in the group like `spawn` does. The handle can be
+    /// `.await`ed on its own for the task's `TaskResult<T>`, or cancelled
+    /// individually with `Task::cancel`, without affecting the rest of the
+    /// group.
+    pub fn spawn_handle<T, Fut>(&self, fut: Fut) -> Task<'a, T>
+    where
+        T: Send + Sync + 'a,
+        Fut: Future<Output = T> + Send + 'a,
+    {
+        let cancel_flag = Arc::new(CondWait::new());
+        let task_cancel_flag = cancel_flag.clone();
+        let (result_tx, result_rx) = async_channel::bounded(1);
+
+        self.spawn(
+            async move { select(task_cancel_flag.wait(), fut).await },
+            move |result| async move {
+                let result = match result {
+                    TaskResult::Cancelled => TaskResult::Cancelled,
+                    TaskResult::Panicked(payload) => TaskResult::Panicked(payload),
+                    TaskResult::Completed(Either::Left(_)) => TaskResult::Cancelled,
+                    TaskResult::Completed(Either::Right(res)) => TaskResult::Completed(res),
+                };
+                result_tx.send(result).await.ok();
+            },
+        );
+
+        Task {
+            cancel_flag,
+            result_rx,
+            recv: None,
+        }
     }
 
     /// Checks if the TaskGroup is empty.
@@ -90,14 +178,38 @@ impl<'a> TaskGroup<'a> {
         self.stop_signal.broadcast().await;
 
         loop {
-            let task = self.tasks.lock().unwrap().pop();
-            if let Some(t) = task {
-                t.cancel().await
-            } else {
-                break;
+            let task = {
+                let mut tasks = self.tasks.lock().unwrap();
+                let id = tasks.keys().next().copied();
+                id.and_then(|id| tasks.remove(&id))
+            };
+            match task {
+                Some(t) => t.cancel().await,
+                None => break,
             }
         }
     }
+
+    /// Waits, without broadcasting the stop signal, until the group is
+    /// empty: every spawned task, including any spawned while waiting, has
+    /// run to completion. Unlike `cancel`, this lets in-flight
+    /// tasks drain naturally, so it's suited for a graceful shutdown where
+    /// new work has already stopped being submitted.
+    pub async fn join(&self) {
+        while !self.is_empty() {
+            smol::Timer::after(JOIN_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Tries to `join` the group within `duration`, falling back to
+    /// `cancel` if the deadline elapses before all tasks finish on their
+    /// own.
+    pub async fn cancel_after(&self, duration: std::time::Duration) {
+        match select(self.join(), smol::Timer::after(duration)).await {
+            Either::Left(_) => {}
+            Either::Right(_) => self.cancel().await,
+        }
+    }
 }
 
 impl Default for TaskGroup<'static> {
@@ -106,11 +218,89 @@ impl Default for TaskGroup<'static> {
     }
 }
 
+impl<'a> Drop for TaskGroup<'a> {
+    /// Broadcasts the stop signal so anything waiting on it directly (for
+    /// example the forwarding task `child` spawns in the parent) isn't left
+    /// waiting forever for a group that's gone and will never call
+    /// `cancel` explicitly.
+    fn drop(&mut self) {
+        let stop_signal = self.stop_signal.clone();
+        self.executor
+            .spawn(async move {
+                stop_signal.broadcast().await;
+            })
+            .detach();
+    }
+}
+
+/// A handle to a single task spawned with `TaskGroup::spawn_handle`.
+///
+/// Awaiting it resolves to the task's `TaskResult<T>` once the task
+/// finishes, and `cancel` lets the caller stop just this task without
+/// touching the rest of the group.
+pub struct Task<'a, T> {
+    cancel_flag: Arc<CondWait>,
+    result_rx: async_channel::Receiver<TaskResult<T>>,
+    // `async_channel::Recv` borrows its `Receiver` and is itself `!Unpin`,
+    // so it can't be created fresh and pinned on the stack on every `poll`
+    // (there'd be nowhere sound to pin it to). Instead the in-flight `recv`
+    // future is boxed, pinned once, and reused across polls.
+    recv: Option<std::pin::Pin<Box<dyn Future<Output = Result<TaskResult<T>, async_channel::RecvError>> + Send + 'a>>>,
+}
+
+impl<'a, T: Send + Sync + 'a> Task<'a, T> {
+    /// Cancels this task and waits for it to finish.
+    pub async fn cancel(self) -> TaskResult<T> {
+        self.cancel_flag.broadcast().await;
+        self.result_rx.recv().await.unwrap_or(TaskResult::Cancelled)
+    }
+}
+
+impl<'a, T: Send + Sync + 'a> Future for Task<'a, T> {
+    type Output = TaskResult<T>;
+
+    fn poll(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        let this = self.get_mut();
+
+        if this.recv.is_none() {
+            let result_rx = this.result_rx.clone();
+            this.recv = Some(Box::pin(async move { result_rx.recv().await }));
+        }
+
+        match this.recv.as_mut().unwrap().as_mut().poll(cx) {
+            std::task::Poll::Ready(result) => {
+                this.recv = None;
+                match result {
+                    Ok(result) => std::task::Poll::Ready(result),
+                    Err(_) => std::task::Poll::Ready(TaskResult::Cancelled),
+                }
+            }
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    }
+}
+
 /// The result of a spawned task.
-#[derive(Debug)]
 pub enum TaskResult<T> {
     Completed(T),
     Cancelled,
+    /// The task's future panicked. Carries the panic payload, as caught by
+    /// `catch_unwind`, so a supervisor can log it or restart the task
+    /// instead of losing it silently.
+    Panicked(Box<dyn std::any::Any + Send>),
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for TaskResult<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TaskResult::Completed(res) => f.debug_tuple("Completed").field(res).finish(),
+            TaskResult::Cancelled => write!(f, "Cancelled"),
+            TaskResult::Panicked(_) => write!(f, "Panicked"),
+        }
+    }
 }
 
 impl<T: std::fmt::Debug> std::fmt::Display for TaskResult<T> {
@@ -118,6 +308,7 @@ impl<T: std::fmt::Debug> std::fmt::Display for TaskResult<T> {
         match self {
             TaskResult::Cancelled => write!(f, "Task cancelled"),
             TaskResult::Completed(res) => write!(f, "Task completed: {:?}", res),
+            TaskResult::Panicked(_) => write!(f, "Task panicked"),
         }
     }
 }
@@ -130,34 +321,47 @@ pub struct TaskHandler {
 
 impl<'a> TaskHandler {
     /// Creates a new task handler
-    fn new<T, Fut, CallbackF, CallbackFut>(
+    fn new<T, Fut, CallbackF, CallbackFut, OnFinish>(
         ex: Executor<'a>,
         fut: Fut,
         callback: CallbackF,
         stop_signal: Arc<CondWait>,
+        on_finish: OnFinish,
     ) -> TaskHandler
     where
         T: Send + Sync + 'a,
         Fut: Future<Output = T> + Send + 'a,
         CallbackF: FnOnce(TaskResult<T>) -> CallbackFut + Send + 'a,
         CallbackFut: Future<Output = ()> + Send + 'a,
+        OnFinish: FnOnce() + Send + 'a,
     {
         let cancel_flag = Arc::new(CondWait::new());
         let cancel_flag_c = cancel_flag.clone();
         let task = ex
             .spawn(async move {
                 // Waits for either the stop signal or the task to complete.
-                let result = select(stop_signal.wait(), fut).await;
+                // The task's future is wrapped in `catch_unwind` so a panic
+                // inside it doesn't silently vanish along with the task.
+                let result = select(
+                    stop_signal.wait(),
+                    std::panic::AssertUnwindSafe(fut).catch_unwind(),
+                )
+                .await;
 
                 let result = match result {
                     Either::Left(_) => TaskResult::Cancelled,
-                    Either::Right(res) => TaskResult::Completed(res),
+                    Either::Right(Ok(res)) => TaskResult::Completed(res),
+                    Either::Right(Err(payload)) => TaskResult::Panicked(payload),
                 };
 
                 // Call the callback
                 callback(result).await;
 
                 cancel_flag_c.signal().await;
+
+                // Reap this task's slot from the group now that it's done,
+                // whether it completed naturally or was cancelled.
+                on_finish();
             })
             .fallible();
 
@@ -238,4 +442,130 @@ mod tests {
             group.cancel().await;
         });
     }
+
+    #[test]
+    fn test_captures_task_panic() {
+        smol::block_on(async {
+            let group = TaskGroup::new();
+
+            group.spawn(
+                async { panic!("boom") },
+                |res| async move {
+                    assert!(matches!(res, TaskResult::Panicked(_)));
+                },
+            );
+
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+            assert!(group.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_join_waits_for_tasks_to_drain() {
+        smol::block_on(async {
+            let group = TaskGroup::new();
+
+            group.spawn(
+                async { smol::Timer::after(std::time::Duration::from_millis(50)).await },
+                |res| async move {
+                    assert!(matches!(res, TaskResult::Completed(_)));
+                },
+            );
+
+            group.join().await;
+            assert!(group.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_cancel_after_falls_back_once_deadline_elapses() {
+        smol::block_on(async {
+            let group = TaskGroup::new();
+
+            group.spawn(future::pending::<()>(), |res| async move {
+                assert!(matches!(res, TaskResult::Cancelled));
+            });
+
+            group
+                .cancel_after(std::time::Duration::from_millis(50))
+                .await;
+            assert!(group.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_reaps_completed_tasks() {
+        smol::block_on(async {
+            let group = TaskGroup::new();
+
+            for _ in 0..10 {
+                group.spawn(future::ready(()), |_| async {});
+            }
+            assert_eq!(group.len(), 10);
+
+            // Completed tasks are reaped asynchronously right after their
+            // callback runs; give them a moment to finish.
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+            assert!(group.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_child_cascading_cancel() {
+        smol::block_on(async {
+            let parent = TaskGroup::new();
+            let child = parent.child();
+
+            child.spawn(future::pending::<()>(), |res| async move {
+                assert!(matches!(res, TaskResult::Cancelled));
+            });
+
+            parent.cancel().await;
+
+            // `cancel()` only guarantees the forwarder task's stop signal
+            // fired, not that the child's own spawned task has since been
+            // polled, run its callback, and reaped itself from the map.
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+            assert!(child.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_child_reaped_from_parent_when_dropped_on_its_own() {
+        smol::block_on(async {
+            let parent = TaskGroup::new();
+            let child = parent.child();
+            assert_eq!(parent.len(), 1);
+
+            // Dropping the child without ever cancelling the parent must
+            // not leave the parent's forwarding task around forever.
+            drop(child);
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+            assert!(parent.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_spawn_handle() {
+        smol::block_on(async {
+            let group = TaskGroup::new();
+
+            let task = group.spawn_handle(future::ready(42));
+            assert!(matches!(task.await, TaskResult::Completed(42)));
+
+            // The underlying group slot is reaped asynchronously right
+            // after the handle's result is delivered; give it a moment.
+            smol::Timer::after(std::time::Duration::from_millis(50)).await;
+            assert!(group.is_empty());
+
+            let task = group.spawn_handle(future::pending::<()>());
+            assert!(matches!(task.cancel().await, TaskResult::Cancelled));
+
+            // Cancelling one handle doesn't affect the rest of the group.
+            group.spawn(future::pending::<()>(), |res| async move {
+                assert!(matches!(res, TaskResult::Cancelled));
+            });
+            group.cancel().await;
+        });
+    }
 }
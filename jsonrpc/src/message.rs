@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const PARSE_ERROR_CODE: i32 = -32700;
+pub const INVALID_REQUEST_ERROR_CODE: i32 = -32600;
+pub const METHOD_NOT_FOUND_ERROR_CODE: i32 = -32601;
+pub const INVALID_PARAMS_ERROR_CODE: i32 = -32602;
+pub const INTERNAL_ERROR_CODE: i32 = -32603;
+
+/// A JSON-RPC request or response id. Per the spec it may be a string, a
+/// number, or null.
+pub type MessageId = Value;
+
+/// A JSON-RPC 2.0 request.
+///
+/// `id` is absent for a notification, a request the server MUST NOT reply
+/// to.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present_id",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub id: Option<MessageId>,
+}
+
+/// Deserializes an `id` field that is present, including an explicit
+/// `null`, as `Some`, so that only an absent `id` marks a notification.
+fn deserialize_present_id<'de, D>(deserializer: D) -> Result<Option<MessageId>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Value::deserialize(deserializer).map(Some)
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", serde_json::to_string(self).unwrap_or_default())
+    }
+}
+
+/// A JSON-RPC 2.0 response.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Response {
+    #[serde(default = "default_jsonrpc_version")]
+    pub jsonrpc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+    pub id: Option<MessageId>,
+}
+
+fn default_jsonrpc_version() -> String {
+    JSONRPC_VERSION.to_string()
+}
+
+// `#[serde(default = "...")]` only kicks in on deserialization, so a plain
+// `#[derive(Default)]` would leave `jsonrpc` as `""` for every response
+// built with `..Default::default()` at construction sites. Implement it by
+// hand so those sites still get the correct `"2.0"`.
+impl Default for Response {
+    fn default() -> Self {
+        Response {
+            jsonrpc: default_jsonrpc_version(),
+            result: None,
+            error: None,
+            id: None,
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", serde_json::to_string(self).unwrap_or_default())
+    }
+}
+
+/// A JSON-RPC 2.0 error object, nested inside a `Response`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Error {
+    pub code: i32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl Error {
+    /// Wraps this error into a `Response` for the given request `id`,
+    /// optionally overriding the `data` attached to the error.
+    pub fn to_response(self, id: Option<MessageId>, data: Option<Value>) -> Response {
+        Response {
+            error: Some(Error {
+                data: data.or(self.data),
+                ..self
+            }),
+            id,
+            ..Default::default()
+        }
+    }
+}
+
+/// A JSON-RPC 2.0 notification: a request-shaped message with no `id`,
+/// used here to push subscription updates to a client.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Notification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+}
+
+impl fmt::Display for Notification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", serde_json::to_string(self).unwrap_or_default())
+    }
+}
+
+/// The `params` payload of a subscription `Notification`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NotificationResult {
+    pub subscription: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_without_id_is_a_notification() {
+        let req: Request = serde_json::from_value(serde_json::json!({
+            "jsonrpc": JSONRPC_VERSION,
+            "method": "ping.send",
+        }))
+        .unwrap();
+        assert_eq!(req.id, None);
+    }
+
+    #[test]
+    fn request_with_id_keeps_it() {
+        let req: Request = serde_json::from_value(serde_json::json!({
+            "jsonrpc": JSONRPC_VERSION,
+            "method": "ping.send",
+            "id": 7,
+        }))
+        .unwrap();
+        assert_eq!(req.id, Some(serde_json::json!(7)));
+    }
+
+    #[test]
+    fn response_default_has_the_jsonrpc_version() {
+        let res = Response::default();
+        assert_eq!(res.jsonrpc, JSONRPC_VERSION);
+    }
+}
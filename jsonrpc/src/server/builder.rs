@@ -0,0 +1,115 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use karyon_core::async_runtime::Executor;
+#[cfg(feature = "tls")]
+use karyon_net::async_rustls::rustls;
+#[cfg(feature = "tcp")]
+use karyon_net::tcp::TcpConfig;
+use karyon_net::Endpoint;
+
+use crate::{PubSubRPCService, RPCService, Result};
+
+use super::interceptor::RpcInterceptor;
+use super::{Server, ServerConfig};
+
+/// Builds a `Server` with a fluent API.
+pub struct ServerBuilder {
+    endpoint: Endpoint,
+    #[cfg(feature = "tcp")]
+    tcp_config: TcpConfig,
+    #[cfg(feature = "tls")]
+    tls_config: Option<rustls::ServerConfig>,
+    services: HashMap<String, Arc<dyn RPCService + 'static>>,
+    pubsub_services: HashMap<String, Arc<dyn PubSubRPCService + 'static>>,
+    max_concurrent_requests_per_conn: Option<usize>,
+    interceptors: Vec<Arc<dyn RpcInterceptor>>,
+    request_timeout: Option<Duration>,
+}
+
+impl ServerBuilder {
+    /// Creates a new builder for a server that will listen on `endpoint`.
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            #[cfg(feature = "tcp")]
+            tcp_config: TcpConfig::default(),
+            #[cfg(feature = "tls")]
+            tls_config: None,
+            services: HashMap::new(),
+            pubsub_services: HashMap::new(),
+            max_concurrent_requests_per_conn: None,
+            interceptors: Vec::new(),
+            request_timeout: None,
+        }
+    }
+
+    /// Registers an RPC service under `name` (the part before the `.` in a
+    /// request's method, e.g. `"name.method"`).
+    pub fn service(mut self, name: impl Into<String>, service: Arc<dyn RPCService>) -> Self {
+        self.services.insert(name.into(), service);
+        self
+    }
+
+    /// Registers a pub/sub RPC service under `name`.
+    pub fn pubsub_service(
+        mut self,
+        name: impl Into<String>,
+        service: Arc<dyn PubSubRPCService>,
+    ) -> Self {
+        self.pubsub_services.insert(name.into(), service);
+        self
+    }
+
+    #[cfg(feature = "tcp")]
+    pub fn tcp_config(mut self, tcp_config: TcpConfig) -> Self {
+        self.tcp_config = tcp_config;
+        self
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls_config(mut self, tls_config: rustls::ServerConfig) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    /// Caps how many requests a single connection may have in flight at
+    /// once. `None` (the default) means unbounded. `0` is treated as `1`
+    /// rather than rejected, since a connection with no permits at all
+    /// could never read another request.
+    pub fn max_concurrent_requests_per_conn(mut self, limit: usize) -> Self {
+        self.max_concurrent_requests_per_conn = Some(limit);
+        self
+    }
+
+    /// Registers a hook that runs, in registration order, around every
+    /// dispatched request.
+    pub fn add_interceptor(mut self, interceptor: Arc<dyn RpcInterceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Caps how long a single service method is allowed to run before it's
+    /// aborted and reported to the client as a timeout. `None` (the
+    /// default) means unbounded.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Builds the server and binds its listener on the configured endpoint.
+    pub async fn build(self, ex: Option<Executor>) -> Result<Arc<Server>> {
+        let config = ServerConfig {
+            endpoint: self.endpoint,
+            #[cfg(feature = "tcp")]
+            tcp_config: self.tcp_config,
+            #[cfg(feature = "tls")]
+            tls_config: self.tls_config,
+            services: self.services,
+            pubsub_services: self.pubsub_services,
+            max_concurrent_requests_per_conn: self.max_concurrent_requests_per_conn,
+            interceptors: self.interceptors,
+            request_timeout: self.request_timeout,
+        };
+        Server::init(config, ex).await
+    }
+}
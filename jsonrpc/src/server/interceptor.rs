@@ -0,0 +1,95 @@
+use async_trait::async_trait;
+
+use crate::message;
+
+/// Context describing the request an `RpcInterceptor` is running around.
+pub struct RequestContext {
+    pub srvc_name: String,
+    pub method_name: String,
+}
+
+/// A hook that runs before and after `Server::handle_request` dispatches to
+/// a service method, letting callers implement cross-cutting concerns such
+/// as authentication, rate limiting, logging, or metrics without forking
+/// the server.
+#[async_trait]
+pub trait RpcInterceptor: Send + Sync {
+    /// Runs after the service/method name has been resolved, but before
+    /// dispatch. Returning an error short-circuits dispatch; the error is
+    /// turned into the JSON-RPC error response sent back to the client.
+    async fn before(&self, ctx: &RequestContext) -> Result<(), message::Error> {
+        let _ = ctx;
+        Ok(())
+    }
+
+    /// Runs after dispatch, with a chance to inspect or mutate the
+    /// response before it's sent.
+    async fn after(&self, ctx: &RequestContext, response: &mut message::Response) {
+        let _ = (ctx, response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct RejectEverything;
+
+    #[async_trait]
+    impl RpcInterceptor for RejectEverything {
+        async fn before(&self, _ctx: &RequestContext) -> Result<(), message::Error> {
+            Err(message::Error {
+                code: -32000,
+                message: "rejected".to_string(),
+                data: None,
+            })
+        }
+    }
+
+    struct TagResponse;
+
+    #[async_trait]
+    impl RpcInterceptor for TagResponse {
+        async fn after(&self, _ctx: &RequestContext, response: &mut message::Response) {
+            response.result = Some(serde_json::json!("tagged"));
+        }
+    }
+
+    fn ctx() -> RequestContext {
+        RequestContext {
+            srvc_name: "foo".to_string(),
+            method_name: "bar".to_string(),
+        }
+    }
+
+    #[test]
+    fn default_hooks_are_no_ops() {
+        struct NoOp;
+        #[async_trait]
+        impl RpcInterceptor for NoOp {}
+
+        smol::block_on(async {
+            assert!(NoOp.before(&ctx()).await.is_ok());
+            let mut response = message::Response::default();
+            NoOp.after(&ctx(), &mut response).await;
+            assert_eq!(response.result, None);
+        });
+    }
+
+    #[test]
+    fn before_hook_can_reject_a_request() {
+        smol::block_on(async {
+            let err = RejectEverything.before(&ctx()).await.unwrap_err();
+            assert_eq!(err.message, "rejected");
+        });
+    }
+
+    #[test]
+    fn after_hook_can_mutate_the_response() {
+        smol::block_on(async {
+            let mut response = message::Response::default();
+            TagResponse.after(&ctx(), &mut response).await;
+            assert_eq!(response.result, Some(serde_json::json!("tagged")));
+        });
+    }
+}